@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use super::{Key, Value, KvPair};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        InvalidDsn(msg: String) {
+            description(msg)
+        }
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Dsn {
+    Memory,
+    RocksDBPath(&'static str),
+}
+
+/// A single raw key/value mutation applied atomically by `Engine::write`.
+pub enum Modify {
+    Put(KvPair),
+    Delete(Key),
+}
+
+/// The raw, version-agnostic key/value store that the `mvcc` layer is built
+/// on top of. `Engine` knows nothing about transactions or timestamps; it
+/// just stores whatever bytes `mvcc` hands it.
+pub trait Engine: Send + Sync {
+    fn get(&self, key: &Key) -> Result<Option<Value>>;
+    fn scan(&self, start_key: &Key, limit: usize) -> Result<Vec<KvPair>>;
+    fn write(&self, batch: Vec<Modify>) -> Result<()>;
+
+    /// Removes every key the engine holds. Used by `mvcc::MvccTxn::restore`
+    /// to make way for a freshly replayed snapshot, so restoring doesn't
+    /// merge with whatever the engine already had.
+    fn clear(&self) -> Result<()>;
+}
+
+pub fn new_engine(dsn: Dsn) -> Result<Box<Engine>> {
+    match dsn {
+        Dsn::Memory => Ok(Box::new(MemoryEngine::new())),
+        Dsn::RocksDBPath(path) => {
+            Err(Error::InvalidDsn(format!("rocksdb engine not wired up yet: {}", path)))
+        }
+    }
+}
+
+struct MemoryEngine {
+    data: RwLock<BTreeMap<Key, Value>>,
+}
+
+impl MemoryEngine {
+    fn new() -> MemoryEngine {
+        MemoryEngine { data: RwLock::new(BTreeMap::new()) }
+    }
+}
+
+impl Engine for MemoryEngine {
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        Ok(self.data.read().unwrap().get(key).cloned())
+    }
+
+    fn scan(&self, start_key: &Key, limit: usize) -> Result<Vec<KvPair>> {
+        Ok(self.data
+            .read()
+            .unwrap()
+            .range(start_key.clone()..)
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    fn write(&self, batch: Vec<Modify>) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        for modify in batch {
+            match modify {
+                Modify::Put((k, v)) => {
+                    data.insert(k, v);
+                }
+                Modify::Delete(k) => {
+                    data.remove(&k);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.data.write().unwrap().clear();
+        Ok(())
+    }
+}
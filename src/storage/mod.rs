@@ -1,7 +1,7 @@
 use std::boxed::FnBox;
 use std::fmt;
 use std::thread::{self, JoinHandle};
-use std::sync::mpsc::{self, Sender};
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver, TrySendError};
 use self::txn::Scheduler;
 
 mod engine;
@@ -16,7 +16,14 @@ pub type Value = Vec<u8>;
 pub type KvPair = (Key, Value);
 pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 
-#[derive(Debug)]
+/// A single change delivered to a range watcher: the key that changed, its
+/// new value (`None` for a delete), and the commit timestamp the change
+/// happened at. During the initial-snapshot replay, `commit_ts` is the
+/// timestamp the snapshot itself was taken at rather than the timestamp
+/// the key was actually committed at.
+pub type WatchEvent = (Key, Option<Value>, u64);
+
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Write {
     Put(KvPair),
     Delete(Key),
@@ -43,8 +50,10 @@ pub enum Command {
     },
     Scan {
         start_key: Key,
+        end_key: Option<Key>,
         limit: usize,
         start_ts: u64,
+        reverse: bool,
         callback: Callback<Vec<Result<KvPair>>>,
     },
     Prewrite {
@@ -57,6 +66,29 @@ pub enum Command {
         commit_ts: u64,
         callback: Callback<()>,
     },
+    Cas {
+        key: Key,
+        expected: Option<Value>,
+        new: Option<Value>,
+        start_ts: u64,
+        callback: Callback<()>,
+    },
+    Watch {
+        start_key: Key,
+        end_key: Key,
+        sender: Sender<WatchEvent>,
+        callback: Callback<()>,
+    },
+    Rollback {
+        start_ts: u64,
+        keys: Vec<Key>,
+        callback: Callback<()>,
+    },
+    Dump { callback: Callback<Vec<u8>> },
+    Restore {
+        bytes: Vec<u8>,
+        callback: Callback<()>,
+    },
 }
 
 impl fmt::Debug for Command {
@@ -65,11 +97,13 @@ impl fmt::Debug for Command {
             Command::Get{ref key, start_ts, ..} => {
                 write!(f, "kv::command::get {:?} @ {}", key, start_ts)
             }
-            Command::Scan{ref start_key, limit, start_ts, ..} => {
+            Command::Scan{ref start_key, ref end_key, limit, start_ts, reverse, ..} => {
                 write!(f,
-                       "kv::command::scan {:?}({}) @ {}",
+                       "kv::command::scan {:?}..{:?}({}{}) @ {}",
                        start_key,
+                       end_key,
                        limit,
+                       if reverse { " reverse" } else { "" },
                        start_ts)
             }
             Command::Prewrite {ref writes, start_ts, ..} => {
@@ -81,22 +115,51 @@ impl fmt::Debug for Command {
             Command::Commit{start_ts, commit_ts, ..} => {
                 write!(f, "kv::command::commit {} -> {}", start_ts, commit_ts)
             }
+            Command::Cas{ref key, start_ts, ..} => {
+                write!(f, "kv::command::cas {:?} @ {}", key, start_ts)
+            }
+            Command::Watch{ref start_key, ref end_key, ..} => {
+                write!(f, "kv::command::watch {:?}..{:?}", start_key, end_key)
+            }
+            Command::Rollback{start_ts, ref keys, ..} => {
+                write!(f, "kv::command::rollback keys({}) @ {}", keys.len(), start_ts)
+            }
+            Command::Dump{..} => write!(f, "kv::command::dump"),
+            Command::Restore{ref bytes, ..} => {
+                write!(f, "kv::command::restore bytes({})", bytes.len())
+            }
         }
     }
 }
 
+/// Tuning knobs for a `Storage` instance.
+#[derive(Debug, Clone, Copy)]
+pub struct StorageOpts {
+    /// Capacity of the bounded channel feeding the `Scheduler` thread.
+    /// Once it's full, `async_*` calls block the caller until the
+    /// scheduler catches up, and `try_async_*` calls return
+    /// `Error::QueueFull` instead of blocking.
+    pub sched_queue_size: usize,
+}
+
+impl Default for StorageOpts {
+    fn default() -> StorageOpts {
+        StorageOpts { sched_queue_size: 1024 }
+    }
+}
+
 pub struct Storage {
-    tx: Sender<Message>,
+    tx: SyncSender<Message>,
     thread: JoinHandle<Result<()>>,
 }
 
 impl Storage {
-    pub fn new(dsn: Dsn) -> Result<Storage> {
+    pub fn new(dsn: Dsn, opts: StorageOpts) -> Result<Storage> {
         let mut scheduler = {
             let engine = try!(engine::new_engine(dsn));
             Scheduler::new(engine)
         };
-        let (tx, rx) = mpsc::channel::<Message>();
+        let (tx, rx) = mpsc::sync_channel::<Message>(opts.sched_queue_size);
         let desc = format!("{:?}", dsn);
         let handle = thread::spawn(move || {
             info!("storage: [{}] started.", desc);
@@ -105,7 +168,10 @@ impl Storage {
                 debug!("recv message: {:?}", msg);
                 match msg {
                     Message::Command(cmd) => scheduler.handle_cmd(cmd),
-                    Message::Close => break,
+                    Message::Close => {
+                        scheduler.close_watchers();
+                        break;
+                    }
                 }
             }
             info!("storage: [{}] closing.", desc);
@@ -123,34 +189,84 @@ impl Storage {
         Ok(())
     }
 
+    fn send(&self, cmd: Command) -> Result<()> {
+        try!(self.tx.send(Message::Command(cmd)));
+        Ok(())
+    }
+
+    /// Like `send`, but never blocks: once the scheduler queue is full
+    /// this returns `Error::QueueFull` instead of waiting for room, so a
+    /// caller fronting an RPC server can shed load deliberately.
+    fn try_send(&self, cmd: Command) -> Result<()> {
+        match self.tx.try_send(Message::Command(cmd)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(Error::QueueFull),
+            Err(TrySendError::Disconnected(msg)) => Err(Error::from(mpsc::SendError(msg))),
+        }
+    }
+
     pub fn async_get(&self,
                      key: Key,
                      start_ts: u64,
                      callback: Callback<Option<Value>>)
                      -> Result<()> {
-        let cmd = Command::Get {
+        self.send(Command::Get {
             key: key,
             start_ts: start_ts,
             callback: callback,
-        };
-        try!(self.tx.send(Message::Command(cmd)));
-        Ok(())
+        })
     }
 
+    pub fn try_async_get(&self,
+                         key: Key,
+                         start_ts: u64,
+                         callback: Callback<Option<Value>>)
+                         -> Result<()> {
+        self.try_send(Command::Get {
+            key: key,
+            start_ts: start_ts,
+            callback: callback,
+        })
+    }
+
+    /// Scans `[start_key, end_key)` (`end_key = None` meaning unbounded
+    /// above) for up to `limit` keys visible at `start_ts`. With
+    /// `reverse` set, results are paged from the largest key below
+    /// `end_key` backward instead of forward from `start_key`.
     pub fn async_scan(&self,
                       start_key: Key,
+                      end_key: Option<Key>,
                       limit: usize,
                       start_ts: u64,
+                      reverse: bool,
                       callback: Callback<Vec<Result<KvPair>>>)
                       -> Result<()> {
-        let cmd = Command::Scan {
+        self.send(Command::Scan {
             start_key: start_key,
+            end_key: end_key,
             limit: limit,
             start_ts: start_ts,
+            reverse: reverse,
             callback: callback,
-        };
-        try!(self.tx.send(Message::Command(cmd)));
-        Ok(())
+        })
+    }
+
+    pub fn try_async_scan(&self,
+                          start_key: Key,
+                          end_key: Option<Key>,
+                          limit: usize,
+                          start_ts: u64,
+                          reverse: bool,
+                          callback: Callback<Vec<Result<KvPair>>>)
+                          -> Result<()> {
+        self.try_send(Command::Scan {
+            start_key: start_key,
+            end_key: end_key,
+            limit: limit,
+            start_ts: start_ts,
+            reverse: reverse,
+            callback: callback,
+        })
     }
 
     pub fn async_prewrite(&self,
@@ -158,13 +274,23 @@ impl Storage {
                           start_ts: u64,
                           callback: Callback<()>)
                           -> Result<()> {
-        let cmd = Command::Prewrite {
+        self.send(Command::Prewrite {
             writes: writes,
             start_ts: start_ts,
             callback: callback,
-        };
-        try!(self.tx.send(Message::Command(cmd)));
-        Ok(())
+        })
+    }
+
+    pub fn try_async_prewrite(&self,
+                              writes: Vec<Write>,
+                              start_ts: u64,
+                              callback: Callback<()>)
+                              -> Result<()> {
+        self.try_send(Command::Prewrite {
+            writes: writes,
+            start_ts: start_ts,
+            callback: callback,
+        })
     }
 
     pub fn async_commit(&self,
@@ -172,13 +298,156 @@ impl Storage {
                         commit_ts: u64,
                         callback: Callback<()>)
                         -> Result<()> {
-        let cmd = Command::Commit {
+        self.send(Command::Commit {
             start_ts: start_ts,
             commit_ts: commit_ts,
             callback: callback,
-        };
-        try!(self.tx.send(Message::Command(cmd)));
-        Ok(())
+        })
+    }
+
+    pub fn try_async_commit(&self,
+                            start_ts: u64,
+                            commit_ts: u64,
+                            callback: Callback<()>)
+                            -> Result<()> {
+        self.try_send(Command::Commit {
+            start_ts: start_ts,
+            commit_ts: commit_ts,
+            callback: callback,
+        })
+    }
+
+    /// Atomically reads the key's latest committed value and, only if it
+    /// byte-equals `expected`, writes `new` (`None` meaning delete). The
+    /// comparison is against the newest committed version, not the one
+    /// visible at `start_ts`; `start_ts` only advances the scheduler's
+    /// timestamp allocator. Pass `expected: None` for a
+    /// create-if-not-exists write. Unlike a
+    /// get-then-prewrite-then-commit sequence issued by the caller, this
+    /// is a single command handled by one `Scheduler` invocation, so it
+    /// cannot race with another command changing the key in between.
+    pub fn async_cas(&self,
+                     key: Key,
+                     expected: Option<Value>,
+                     new: Option<Value>,
+                     start_ts: u64,
+                     callback: Callback<()>)
+                     -> Result<()> {
+        self.send(Command::Cas {
+            key: key,
+            expected: expected,
+            new: new,
+            start_ts: start_ts,
+            callback: callback,
+        })
+    }
+
+    pub fn try_async_cas(&self,
+                         key: Key,
+                         expected: Option<Value>,
+                         new: Option<Value>,
+                         start_ts: u64,
+                         callback: Callback<()>)
+                         -> Result<()> {
+        self.try_send(Command::Cas {
+            key: key,
+            expected: expected,
+            new: new,
+            start_ts: start_ts,
+            callback: callback,
+        })
+    }
+
+    /// Subscribes to every mutation committed to a key in
+    /// `[start_key, end_key)`. The returned `Receiver` first replays every
+    /// key already visible right now as `Put` events (the initial
+    /// snapshot, taken at the scheduler's own current timestamp rather
+    /// than a caller-chosen one, so no commit can land in the gap between
+    /// the snapshot and live streaming), then streams live changes as
+    /// they commit. The stream ends when `Storage` is stopped or the
+    /// `Scheduler` otherwise drops the watcher.
+    pub fn async_watch(&self, start_key: Key, end_key: Key, callback: Callback<()>) -> Result<Receiver<WatchEvent>> {
+        let (tx, rx) = mpsc::channel();
+        try!(self.send(Command::Watch {
+            start_key: start_key,
+            end_key: end_key,
+            sender: tx,
+            callback: callback,
+        }));
+        Ok(rx)
+    }
+
+    pub fn try_async_watch(&self,
+                           start_key: Key,
+                           end_key: Key,
+                           callback: Callback<()>)
+                           -> Result<Receiver<WatchEvent>> {
+        let (tx, rx) = mpsc::channel();
+        try!(self.try_send(Command::Watch {
+            start_key: start_key,
+            end_key: end_key,
+            sender: tx,
+            callback: callback,
+        }));
+        Ok(rx)
+    }
+
+    /// Abandons the transaction at `start_ts`, releasing its prewrite lock
+    /// on each of `keys` so they stop conflicting with other commands.
+    /// Idempotent: a key that isn't locked by `start_ts` (already rolled
+    /// back, already committed, or never locked) is left untouched rather
+    /// than erroring, so a coordinator can batch-clean up an abandoned
+    /// transaction without first checking which locks are still live.
+    pub fn async_rollback(&self, start_ts: u64, keys: Vec<Key>, callback: Callback<()>) -> Result<()> {
+        self.send(Command::Rollback {
+            start_ts: start_ts,
+            keys: keys,
+            callback: callback,
+        })
+    }
+
+    pub fn try_async_rollback(&self,
+                              start_ts: u64,
+                              keys: Vec<Key>,
+                              callback: Callback<()>)
+                              -> Result<()> {
+        self.try_send(Command::Rollback {
+            start_ts: start_ts,
+            keys: keys,
+            callback: callback,
+        })
+    }
+
+    /// Serializes every committed key/value/version into a CBOR byte
+    /// stream (via `serde_cbor`) that `async_restore` can later replay
+    /// into any `Engine`, independent of the `Dsn` it was dumped from.
+    pub fn async_dump(&self, callback: Callback<Vec<u8>>) -> Result<()> {
+        self.send(Command::Dump { callback: callback })
+    }
+
+    pub fn try_async_dump(&self, callback: Callback<Vec<u8>>) -> Result<()> {
+        self.try_send(Command::Dump { callback: callback })
+    }
+
+    /// Replaces the engine's entire committed state with a snapshot
+    /// previously produced by `async_dump` — anything committed since the
+    /// dump, or present in the target engine but absent from it, is
+    /// discarded. Fails with `Error::RestoreConflict` if any transaction
+    /// holds a live prewrite lock at the time of the restore, since
+    /// clearing the engine underneath an in-flight commit would corrupt
+    /// it.
+    pub fn async_restore(&self, bytes: Vec<u8>, callback: Callback<()>) -> Result<()> {
+        self.send(Command::Restore {
+            bytes: bytes,
+            callback: callback,
+        })
+    }
+
+    pub fn try_async_restore(&self, bytes: Vec<u8>, callback: Callback<()>) -> Result<()> {
+        self.try_send(Command::Restore {
+            bytes: bytes,
+            callback: callback,
+        })
     }
 }
 
@@ -216,6 +485,20 @@ quick_error! {
             cause(err)
             description(err.description())
         }
+        CasFailed(key: Key) {
+            description("compare-and-swap precondition failed: key's current value did not match `expected`")
+        }
+        QueueFull {
+            description("scheduler queue is full")
+        }
+        Cbor(err: ::serde_cbor::Error) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        RestoreConflict {
+            description("cannot restore: transactions are still holding prewrite locks")
+        }
         Other(err: Box<::std::any::Any + Send>) {
             from()
         }
@@ -226,7 +509,7 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[cfg(test)]
 mod tests {
-    use super::{Dsn, Storage, Result, Value, Callback, Write};
+    use super::{Dsn, Storage, StorageOpts, Result, KvPair, Value, Callback, Write};
 
     fn expect_get_none() -> Callback<Option<Value>> {
         Box::new(|x: Result<Option<Value>>| assert_eq!(x.unwrap(), None))
@@ -236,6 +519,13 @@ mod tests {
         Box::new(move |x: Result<Option<Value>>| assert_eq!(x.unwrap().unwrap(), v))
     }
 
+    fn expect_kvs(pairs: Vec<KvPair>) -> Callback<Vec<Result<KvPair>>> {
+        Box::new(move |x: Result<Vec<Result<KvPair>>>| {
+            let got: Vec<KvPair> = x.unwrap().into_iter().map(|r| r.unwrap()).collect();
+            assert_eq!(got, pairs);
+        })
+    }
+
     fn expect_ok() -> Callback<()> {
         Box::new(|x: Result<()>| assert!(x.is_ok()))
     }
@@ -244,9 +534,13 @@ mod tests {
         Box::new(|x: Result<()>| assert!(x.is_err()))
     }
 
+    fn expect_bytes(into: ::std::sync::Arc<::std::sync::Mutex<Vec<u8>>>) -> Callback<Vec<u8>> {
+        Box::new(move |x: Result<Vec<u8>>| *into.lock().unwrap() = x.unwrap())
+    }
+
     #[test]
     fn test_get_put() {
-        let storage = Storage::new(Dsn::Memory).unwrap();
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
         storage.async_get(vec![b'x'], 100u64, expect_get_none()).unwrap();
         storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"100".to_vec()))], 100, expect_ok()).unwrap();
         storage.async_commit(100u64, 101u64, expect_ok()).unwrap();
@@ -257,7 +551,7 @@ mod tests {
 
     #[test]
     fn test_txn() {
-        let storage = Storage::new(Dsn::Memory).unwrap();
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
         storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"100".to_vec()))], 100, expect_ok()).unwrap();
         storage.async_prewrite(vec![Write::Put((b"y".to_vec(), b"101".to_vec()))], 101, expect_ok()).unwrap();
         storage.async_commit(100u64, 110u64, expect_ok()).unwrap();
@@ -267,4 +561,177 @@ mod tests {
         storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"105".to_vec()))], 105, expect_fail()).unwrap();
         storage.stop().unwrap();
     }
+
+    #[test]
+    fn test_rollback() {
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"100".to_vec()))], 100, expect_ok()).unwrap();
+        storage.async_commit(100u64, 110u64, expect_ok()).unwrap();
+
+        // an abandoned prewrite leaves a lock behind that blocks any other
+        // transaction from touching the same keys...
+        storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"105".to_vec())),
+                                    Write::Put((b"y".to_vec(), b"1".to_vec()))],
+                               115,
+                               expect_ok())
+            .unwrap();
+        storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"200".to_vec()))], 120, expect_fail()).unwrap();
+
+        // ...until it's rolled back, batching both of its keys in one command.
+        storage.async_rollback(115, vec![b"x".to_vec(), b"y".to_vec()], expect_ok()).unwrap();
+        storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"200".to_vec()))], 120, expect_ok()).unwrap();
+        storage.async_commit(120u64, 121u64, expect_ok()).unwrap();
+        storage.async_get(vec![b'x'], 130u64, expect_get_val(b"200".to_vec())).unwrap();
+        storage.async_get(vec![b'y'], 130u64, expect_get_none()).unwrap();
+
+        // rolling back the same (now-absent) lock again, or a key that was
+        // never locked, is a no-op rather than an error.
+        storage.async_rollback(115, vec![b"x".to_vec(), b"never-locked".to_vec()], expect_ok()).unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_cas() {
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        // create-if-not-exists: succeeds once, fails the second time.
+        storage.async_cas(b"x".to_vec(), None, Some(b"1".to_vec()), 100, expect_ok()).unwrap();
+        storage.async_cas(b"x".to_vec(), None, Some(b"2".to_vec()), 101, expect_fail()).unwrap();
+        storage.async_get(vec![b'x'], 200u64, expect_get_val(b"1".to_vec())).unwrap();
+        // compare-and-swap with a stale expected value fails without
+        // touching the key.
+        storage.async_cas(b"x".to_vec(), Some(b"wrong".to_vec()), Some(b"2".to_vec()), 200, expect_fail()).unwrap();
+        storage.async_get(vec![b'x'], 300u64, expect_get_val(b"1".to_vec())).unwrap();
+        // a matching expected value swaps the value in.
+        storage.async_cas(b"x".to_vec(), Some(b"1".to_vec()), Some(b"2".to_vec()), 300, expect_ok()).unwrap();
+        storage.async_get(vec![b'x'], 400u64, expect_get_val(b"2".to_vec())).unwrap();
+        // `new: None` deletes the key.
+        storage.async_cas(b"x".to_vec(), Some(b"2".to_vec()), None, 400, expect_ok()).unwrap();
+        storage.async_get(vec![b'x'], 500u64, expect_get_none()).unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_cas_after_normal_commit() {
+        // a CAS must be able to see and supersede a value committed by an
+        // ordinary prewrite/commit pair, not just values from earlier CAS
+        // calls: its internal timestamps have to land above the client's,
+        // both for the conflict check (reading the prior commit) and for
+        // the result to actually be the newest visible version afterward.
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        storage.async_prewrite(vec![Write::Put((b"x".to_vec(), b"1".to_vec()))], 100, expect_ok()).unwrap();
+        storage.async_commit(100, 110, expect_ok()).unwrap();
+        storage.async_cas(b"x".to_vec(), Some(b"1".to_vec()), Some(b"2".to_vec()), 120, expect_ok()).unwrap();
+        storage.async_get(vec![b'x'], 130u64, expect_get_val(b"2".to_vec())).unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_watch() {
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        storage.async_prewrite(vec![Write::Put((b"a".to_vec(), b"1".to_vec()))], 10, expect_ok()).unwrap();
+        storage.async_commit(10, 11, expect_ok()).unwrap();
+
+        let rx = storage.async_watch(b"a".to_vec(), b"z".to_vec(), expect_ok()).unwrap();
+        // the initial snapshot replays everything already visible right
+        // now, reporting the scheduler's current timestamp (11, from the
+        // commit above) rather than any timestamp the caller picked.
+        assert_eq!(rx.recv().unwrap(), (b"a".to_vec(), Some(b"1".to_vec()), 11));
+
+        // a later commit inside the watched range streams as a live event.
+        storage.async_prewrite(vec![Write::Put((b"b".to_vec(), b"2".to_vec()))], 30, expect_ok()).unwrap();
+        storage.async_commit(30, 31, expect_ok()).unwrap();
+        assert_eq!(rx.recv().unwrap(), (b"b".to_vec(), Some(b"2".to_vec()), 31));
+
+        // a commit outside the watched range (key "z" is the exclusive end) is not delivered.
+        storage.async_prewrite(vec![Write::Put((b"z".to_vec(), b"3".to_vec()))], 40, expect_ok()).unwrap();
+        storage.async_commit(40, 41, expect_ok()).unwrap();
+
+        storage.stop().unwrap();
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_scan_bounded_and_reverse() {
+        let storage = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        for &(k, v) in &[(b'a', b"1"), (b'b', b"2"), (b'c', b"3"), (b'd', b"4")] {
+            storage.async_prewrite(vec![Write::Put((vec![k], v.to_vec()))], 10, expect_ok()).unwrap();
+            storage.async_commit(10, 11, expect_ok()).unwrap();
+        }
+
+        // [b, d) forward, bounded above by the exclusive end_key.
+        storage.async_scan(vec![b'b'],
+                          Some(vec![b'd']),
+                          10,
+                          20,
+                          false,
+                          expect_kvs(vec![(vec![b'b'], b"2".to_vec()), (vec![b'c'], b"3".to_vec())]))
+            .unwrap();
+
+        // the same range walked backward from just below "d".
+        storage.async_scan(vec![b'b'],
+                          Some(vec![b'd']),
+                          10,
+                          20,
+                          true,
+                          expect_kvs(vec![(vec![b'c'], b"3".to_vec()), (vec![b'b'], b"2".to_vec())]))
+            .unwrap();
+
+        // `limit` still caps a reverse scan, keeping only the keys closest to end_key.
+        storage.async_scan(vec![b'a'],
+                          Some(vec![b'd']),
+                          1,
+                          20,
+                          true,
+                          expect_kvs(vec![(vec![b'c'], b"3".to_vec())]))
+            .unwrap();
+
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_try_async_does_not_block() {
+        // `try_async_*` shares the same scheduler behavior as `async_*`;
+        // it just never blocks the caller waiting for queue room.
+        // Exercising `Error::QueueFull` itself needs a saturated,
+        // non-draining scheduler queue, which isn't something a
+        // deterministic single-threaded test can set up. The queue has
+        // room for all three commands below, so none of them can see
+        // `QueueFull` however slowly the scheduler drains it.
+        let storage = Storage::new(Dsn::Memory, StorageOpts { sched_queue_size: 3 }).unwrap();
+        storage.try_async_prewrite(vec![Write::Put((b"x".to_vec(), b"1".to_vec()))], 10, expect_ok()).unwrap();
+        storage.try_async_commit(10, 11, expect_ok()).unwrap();
+        storage.try_async_get(vec![b'x'], 20, expect_get_val(b"1".to_vec())).unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let src = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        src.async_prewrite(vec![Write::Put((b"x".to_vec(), b"1".to_vec()))], 10, expect_ok()).unwrap();
+        src.async_commit(10, 11, expect_ok()).unwrap();
+        src.async_prewrite(vec![Write::Put((b"y".to_vec(), b"2".to_vec()))], 20, expect_ok()).unwrap();
+        src.async_commit(20, 21, expect_ok()).unwrap();
+
+        let dumped = ::std::sync::Arc::new(::std::sync::Mutex::new(Vec::new()));
+        src.async_dump(expect_bytes(dumped.clone())).unwrap();
+        src.stop().unwrap();
+        let bytes = dumped.lock().unwrap().clone();
+        assert!(!bytes.is_empty());
+
+        // restoring replaces the target's entire contents: a key that
+        // existed in the target but not in the dump is gone afterward,
+        // not merged alongside the restored keys.
+        let dst = Storage::new(Dsn::Memory, StorageOpts::default()).unwrap();
+        dst.async_prewrite(vec![Write::Put((b"w".to_vec(), b"stale".to_vec()))], 1, expect_ok()).unwrap();
+        dst.async_commit(1, 2, expect_ok()).unwrap();
+        dst.async_restore(bytes.clone(), expect_ok()).unwrap();
+        dst.async_get(vec![b'x'], 100u64, expect_get_val(b"1".to_vec())).unwrap();
+        dst.async_get(vec![b'y'], 100u64, expect_get_val(b"2".to_vec())).unwrap();
+        dst.async_get(vec![b'w'], 100u64, expect_get_none()).unwrap();
+
+        // restoring on top of a live prewrite lock is rejected outright.
+        dst.async_prewrite(vec![Write::Put((b"z".to_vec(), b"3".to_vec()))], 30, expect_ok()).unwrap();
+        dst.async_restore(bytes, expect_fail()).unwrap();
+        dst.stop().unwrap();
+    }
 }
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::{Key, Value, KvPair, Write};
+use super::engine::{Engine, Modify};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        KeyIsLocked(key: Key, start_ts: u64) {
+            description("key is locked by another transaction")
+        }
+        WriteConflict(key: Key) {
+            description("write conflict")
+        }
+        Engine(err: super::engine::Error) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Cap on raw (key, version) pairs pulled from the engine per `scan` call.
+/// `MemoryEngine` holds everything in process memory anyway, so this only
+/// guards against an unbounded range turning one command into an
+/// unbounded amount of work.
+const RAW_SCAN_BUDGET: usize = 100_000;
+
+/// A prewritten-but-not-yet-committed write, keyed by the user key it locks.
+pub struct Lock {
+    pub start_ts: u64,
+    pub write: Write,
+}
+
+pub type LockTable = Mutex<HashMap<Key, Lock>>;
+
+/// Encodes committed versions of a user key as `key ++ !commit_ts` (the
+/// timestamp is stored big-endian and bit-flipped) so that, for a fixed
+/// user key, ascending byte order also walks commit timestamps from
+/// newest to oldest. This lets a single `Engine::scan` both find the
+/// version visible at a given `start_ts` and enumerate every version of
+/// every key.
+fn encode_key(key: &Key, commit_ts: u64) -> Key {
+    let mut encoded = key.clone();
+    let inv = !commit_ts;
+    for i in 0..8 {
+        encoded.push((inv >> (56 - i * 8)) as u8);
+    }
+    encoded
+}
+
+fn decode_ts(suffix: &[u8]) -> u64 {
+    let mut inv = 0u64;
+    for &b in suffix {
+        inv = (inv << 8) | (b as u64);
+    }
+    !inv
+}
+
+fn encode_write(write: &Write) -> Value {
+    match *write {
+        Write::Put((_, ref value)) => {
+            let mut encoded = vec![b'P'];
+            encoded.extend_from_slice(value);
+            encoded
+        }
+        Write::Delete(_) => vec![b'D'],
+        Write::Lock(_) => vec![b'L'],
+    }
+}
+
+fn decode_write(value: &[u8]) -> Option<Value> {
+    match value.first() {
+        Some(&b'P') => Some(value[1..].to_vec()),
+        _ => None,
+    }
+}
+
+/// Unlike `decode_write`, reconstructs the full `Write` a version was
+/// encoded from (rather than collapsing it to the visible value), since
+/// `dump`/`restore` round-trip the raw record, not just what a reader
+/// would see.
+fn decode_write_full(key: &Key, value: &[u8]) -> Write {
+    match value.first() {
+        Some(&b'P') => Write::Put((key.clone(), value[1..].to_vec())),
+        Some(&b'D') => Write::Delete(key.clone()),
+        _ => Write::Lock(key.clone()),
+    }
+}
+
+/// One committed version of one key, in the wire format `dump`/`restore`
+/// exchange with the outside world via CBOR.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub key: Key,
+    pub commit_ts: u64,
+    pub write: Write,
+}
+
+/// A view over the MVCC store for a single command: it borrows the raw
+/// `Engine` and the scheduler-wide lock table so reads can see pending
+/// locks and writes can take them.
+pub struct MvccTxn<'a> {
+    engine: &'a Engine,
+    locks: &'a LockTable,
+}
+
+impl<'a> MvccTxn<'a> {
+    pub fn new(engine: &'a Engine, locks: &'a LockTable) -> MvccTxn<'a> {
+        MvccTxn {
+            engine: engine,
+            locks: locks,
+        }
+    }
+
+    /// Returns the value visible at `start_ts`, i.e. the value written by
+    /// the latest commit with `commit_ts <= start_ts`.
+    pub fn get(&self, key: &Key, start_ts: u64) -> Result<Option<Value>> {
+        {
+            let locks = self.locks.lock().unwrap();
+            if let Some(lock) = locks.get(key) {
+                if lock.start_ts <= start_ts {
+                    return Err(Error::KeyIsLocked(key.clone(), lock.start_ts));
+                }
+            }
+        }
+        self.latest_visible(key, start_ts)
+    }
+
+    fn latest_visible(&self, key: &Key, start_ts: u64) -> Result<Option<Value>> {
+        let seek = encode_key(key, start_ts);
+        for (enc_key, value) in try!(self.engine.scan(&seek, 1)) {
+            if enc_key.len() != key.len() + 8 || &enc_key[..key.len()] != key.as_slice() {
+                break;
+            }
+            return Ok(decode_write(&value));
+        }
+        Ok(None)
+    }
+
+    /// The commit timestamp of the newest committed version of `key`, if
+    /// any exists, regardless of visibility.
+    fn latest_commit_ts(&self, key: &Key) -> Result<Option<u64>> {
+        let seek = encode_key(key, ::std::u64::MAX);
+        for (enc_key, _) in try!(self.engine.scan(&seek, 1)) {
+            if enc_key.len() != key.len() + 8 || &enc_key[..key.len()] != key.as_slice() {
+                break;
+            }
+            return Ok(Some(decode_ts(&enc_key[key.len()..])));
+        }
+        Ok(None)
+    }
+
+    /// Locks every key in `writes` for `start_ts`, failing the whole batch
+    /// if any key is already locked by a different transaction or has been
+    /// committed at or after `start_ts`.
+    pub fn prewrite(&self, writes: Vec<Write>, start_ts: u64) -> Result<()> {
+        let mut locks = self.locks.lock().unwrap();
+        for w in &writes {
+            if let Some(lock) = locks.get(w.key()) {
+                if lock.start_ts != start_ts {
+                    return Err(Error::KeyIsLocked(w.key().to_vec(), lock.start_ts));
+                }
+                continue;
+            }
+            if let Some(commit_ts) = try!(self.latest_commit_ts(&w.key().to_vec())) {
+                if commit_ts >= start_ts {
+                    return Err(Error::WriteConflict(w.key().to_vec()));
+                }
+            }
+        }
+        for w in writes {
+            let key = w.key().to_vec();
+            locks.insert(key,
+                         Lock {
+                             start_ts: start_ts,
+                             write: w,
+                         });
+        }
+        Ok(())
+    }
+
+    /// Releases the prewrite lock held for `start_ts` on each of `keys`,
+    /// abandoning whatever write it was holding. Idempotent: a key with
+    /// no lock, or one locked by a different transaction, is left alone
+    /// rather than treated as an error, so a coordinator can clean up an
+    /// abandoned transaction (or retry a rollback) without first having
+    /// to know exactly which keys are still locked.
+    pub fn rollback(&self, keys: &[Key], start_ts: u64) -> Result<()> {
+        let mut locks = self.locks.lock().unwrap();
+        for key in keys {
+            let locked_by_us = locks.get(key).map_or(false, |lock| lock.start_ts == start_ts);
+            if locked_by_us {
+                locks.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves up to `limit` keys in `[start_key, end_key)` (`end_key =
+    /// None` meaning unbounded above) to the value each is visible as at
+    /// `start_ts`. Keys with no version visible at `start_ts` (not yet
+    /// written, or deleted) are skipped rather than counted against
+    /// `limit`.
+    ///
+    /// When `reverse` is set, every key in range is still resolved in
+    /// ascending order (the underlying `Engine` only walks forward) but
+    /// the resolved results are then walked from `end_key` backward
+    /// toward `start_key` before `limit` is applied, so the caller sees
+    /// the same pages it would get by seeking to the largest key below
+    /// `end_key` and stepping backward.
+    ///
+    /// Only the first `RAW_SCAN_BUDGET` raw versions at or after
+    /// `start_key` are read from the engine; keys beyond them are not
+    /// resolved, so a range holding more versions than that returns fewer
+    /// keys than it contains, and a reverse scan then starts from the last
+    /// key read rather than from the largest key below `end_key`.
+    pub fn scan(&self,
+                start_key: &Key,
+                end_key: Option<&Key>,
+                limit: usize,
+                start_ts: u64,
+                reverse: bool)
+                -> Result<Vec<KvPair>> {
+        let seek = encode_key(start_key, ::std::u64::MAX);
+        let raw = try!(self.engine.scan(&seek, RAW_SCAN_BUDGET));
+        let mut results = Vec::new();
+        let mut i = 0;
+        while i < raw.len() {
+            let enc_key = raw[i].0.clone();
+            if enc_key.len() < 8 {
+                i += 1;
+                continue;
+            }
+            let user_key = enc_key[..enc_key.len() - 8].to_vec();
+            if let Some(end) = end_key {
+                if &user_key >= end {
+                    break;
+                }
+            }
+            let mut visible = None;
+            let mut j = i;
+            while j < raw.len() && raw[j].0.len() >= 8 &&
+                  raw[j].0[..raw[j].0.len() - 8] == user_key[..] {
+                if visible.is_none() {
+                    let commit_ts = decode_ts(&raw[j].0[user_key.len()..]);
+                    if commit_ts <= start_ts {
+                        visible = Some(decode_write(&raw[j].1));
+                    }
+                }
+                j += 1;
+            }
+            if let Some(Some(value)) = visible {
+                results.push((user_key, value));
+            }
+            i = j;
+        }
+        if reverse {
+            results.reverse();
+        }
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// Finalizes every lock held for `start_ts`, writing the locked values
+    /// at `commit_ts` and releasing the locks. Returns the user-visible
+    /// effect of each finalized write (`Some(value)` for a put, `None` for
+    /// a delete) so callers can fan it out to watchers; `Write::Lock`
+    /// entries just release their lock and produce no entry.
+    pub fn commit(&self, start_ts: u64, commit_ts: u64) -> Result<Vec<(Key, Option<Value>)>> {
+        let mut locks = self.locks.lock().unwrap();
+        let keys: Vec<Key> = locks.iter()
+            .filter(|&(_, lock)| lock.start_ts == start_ts)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut batch = Vec::with_capacity(keys.len());
+        let mut changes = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let lock = locks.remove(key).unwrap();
+            if let Write::Lock(_) = lock.write {
+                continue;
+            }
+            let enc_key = encode_key(key, commit_ts);
+            let value = match lock.write {
+                Write::Put((_, ref value)) => Some(value.clone()),
+                Write::Delete(_) => None,
+                Write::Lock(_) => unreachable!(),
+            };
+            batch.push(Modify::Put((enc_key, encode_write(&lock.write))));
+            changes.push((key.clone(), value));
+        }
+        try!(self.engine.write(batch));
+        Ok(changes)
+    }
+
+    /// Collects every committed version of every key in the engine, for
+    /// `Scheduler::handle_dump` to serialize. Walks the whole keyspace by
+    /// repeatedly scanning past the last key seen, since `Engine::scan`
+    /// only takes a lower bound and a limit.
+    pub fn all_records(&self) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        let mut seek: Key = Vec::new();
+        loop {
+            let raw = try!(self.engine.scan(&seek, RAW_SCAN_BUDGET));
+            if raw.is_empty() {
+                break;
+            }
+            let got = raw.len();
+            for &(ref enc_key, ref value) in &raw {
+                if enc_key.len() < 8 {
+                    continue;
+                }
+                let key = enc_key[..enc_key.len() - 8].to_vec();
+                let commit_ts = decode_ts(&enc_key[key.len()..]);
+                let write = decode_write_full(&key, value);
+                records.push(Record {
+                    key: key,
+                    commit_ts: commit_ts,
+                    write: write,
+                });
+            }
+            seek = raw[got - 1].0.clone();
+            seek.push(0);
+            if got < RAW_SCAN_BUDGET {
+                break;
+            }
+        }
+        Ok(records)
+    }
+
+    /// Replaces the engine's entire contents with a previously dumped
+    /// snapshot: clears everything the engine currently holds, then
+    /// writes the records in. Bypasses the lock table entirely; it's the
+    /// caller's responsibility (see `Scheduler::handle_restore`) to
+    /// ensure no live lock conflicts with the records being restored
+    /// first.
+    pub fn restore(&self, records: Vec<Record>) -> Result<()> {
+        try!(self.engine.clear());
+        let batch = records.into_iter()
+            .map(|r| Modify::Put((encode_key(&r.key, r.commit_ts), encode_write(&r.write))))
+            .collect();
+        try!(self.engine.write(batch));
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+use serde_cbor;
+
+use super::{Command, Write, Key, Value, WatchEvent};
+use super::engine::Engine;
+use super::mvcc::{MvccTxn, LockTable, Record};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Mvcc(err: super::mvcc::Error) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// A registered range watcher: every commit that touches a key in
+/// `[start_key, end_key)` is turned into a `WatchEvent` on `sender`.
+struct Watcher {
+    start_key: Key,
+    end_key: Key,
+    sender: Sender<WatchEvent>,
+}
+
+impl Watcher {
+    fn matches(&self, key: &Key) -> bool {
+        key.as_slice() >= self.start_key.as_slice() && key.as_slice() < self.end_key.as_slice()
+    }
+}
+
+/// Runs every `Command` the `Storage` worker thread receives against the
+/// MVCC store. Commands are handled one at a time on a single thread, so a
+/// multi-step command (e.g. `Cas`'s read-compare-write) is atomic with
+/// respect to every other command for free.
+pub struct Scheduler {
+    engine: Box<Engine>,
+    locks: LockTable,
+    next_ts: Mutex<u64>,
+    watchers: Mutex<Vec<Watcher>>,
+}
+
+impl Scheduler {
+    pub fn new(engine: Box<Engine>) -> Scheduler {
+        Scheduler {
+            engine: engine,
+            locks: Mutex::new(HashMap::new()),
+            next_ts: Mutex::new(1),
+            watchers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Drops every registered watcher, closing its channel so the
+    /// subscriber's stream ends. Called when the scheduler is shutting
+    /// down so watchers aren't left waiting on a sender that will never
+    /// send again.
+    pub fn close_watchers(&mut self) {
+        self.watchers.lock().unwrap().clear();
+    }
+
+    fn txn(&self) -> MvccTxn {
+        MvccTxn::new(self.engine.as_ref(), &self.locks)
+    }
+
+    /// Hands out a fresh, monotonically increasing timestamp for internal
+    /// prewrite/commit pairs that don't come from a client-supplied ts.
+    /// Guaranteed to land above every client timestamp `handle_cmd` has
+    /// observed so far (see `observe_ts`), so an internally-timestamped
+    /// command (e.g. `Cas`'s own prewrite/commit pair) never sorts below
+    /// the state a client read to decide to issue it, and never collides
+    /// with a timestamp a client might reuse later.
+    fn alloc_ts(&self) -> u64 {
+        let mut next_ts = self.next_ts.lock().unwrap();
+        let ts = *next_ts;
+        *next_ts += 1;
+        ts
+    }
+
+    /// Bumps the internal timestamp allocator strictly past `ts`. Called
+    /// with every client-supplied timestamp `handle_cmd` sees, so
+    /// `alloc_ts` never hands out a timestamp a client's own commands
+    /// already rely on being above or below.
+    fn observe_ts(&self, ts: u64) {
+        let mut next_ts = self.next_ts.lock().unwrap();
+        if ts >= *next_ts {
+            *next_ts = ts + 1;
+        }
+    }
+
+    /// An upper bound on every timestamp committed so far: since commands
+    /// run one at a time, nothing with a higher commit_ts than this has
+    /// been applied yet.
+    fn current_ts(&self) -> u64 {
+        self.next_ts.lock().unwrap().saturating_sub(1)
+    }
+
+    pub fn handle_cmd(&mut self, cmd: Command) {
+        match cmd {
+            Command::Get { key, start_ts, callback } => {
+                self.observe_ts(start_ts);
+                callback(to_storage_result(self.txn().get(&key, start_ts)))
+            }
+            Command::Scan { start_key, end_key, limit, start_ts, reverse, callback } => {
+                self.observe_ts(start_ts);
+                let result = self.txn()
+                    .scan(&start_key, end_key.as_ref(), limit, start_ts, reverse)
+                    .map(|pairs| pairs.into_iter().map(Ok).collect())
+                    .map_err(to_storage_err);
+                callback(result)
+            }
+            Command::Prewrite { writes, start_ts, callback } => {
+                self.observe_ts(start_ts);
+                callback(to_storage_result(self.txn().prewrite(writes, start_ts)))
+            }
+            Command::Commit { start_ts, commit_ts, callback } => {
+                self.observe_ts(commit_ts);
+                let result = to_storage_result(self.txn().commit(start_ts, commit_ts));
+                callback(result.map(|changes| self.dispatch_watch_events(changes, commit_ts)))
+            }
+            Command::Cas { key, expected, new, start_ts, callback } => {
+                self.observe_ts(start_ts);
+                callback(self.handle_cas(key, expected, new))
+            }
+            Command::Watch { start_key, end_key, sender, callback } => {
+                callback(self.register_watch(start_key, end_key, sender))
+            }
+            Command::Rollback { start_ts, keys, callback } => {
+                self.observe_ts(start_ts);
+                callback(to_storage_result(self.txn().rollback(&keys, start_ts)))
+            }
+            Command::Dump { callback } => callback(self.handle_dump()),
+            Command::Restore { bytes, callback } => callback(self.handle_restore(bytes)),
+        }
+    }
+
+    /// Sends every finalized write that falls in a watcher's range to that
+    /// watcher, dropping watchers whose receiver has gone away.
+    fn dispatch_watch_events(&self, changes: Vec<(Key, Option<Value>)>, commit_ts: u64) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watcher| {
+            let mut alive = true;
+            for &(ref key, ref value) in &changes {
+                if watcher.matches(key) {
+                    if watcher.sender.send((key.clone(), value.clone(), commit_ts)).is_err() {
+                        alive = false;
+                    }
+                }
+            }
+            alive
+        });
+    }
+
+    /// Registers a watcher for `[start_key, end_key)`, first replaying
+    /// every key already visible right now as an initial snapshot of
+    /// `Put` events before it starts receiving live changes.
+    ///
+    /// The snapshot is taken at the scheduler's own current high-water
+    /// mark rather than a caller-supplied timestamp: commands run one at
+    /// a time, so anything already committed by the time this command
+    /// runs is guaranteed to show up in the snapshot below, and anything
+    /// committed afterward is guaranteed to arrive as a live event — no
+    /// caller-chosen timestamp could close that gap, since any commit
+    /// that lands between a past timestamp and this registration would
+    /// be invisible to both the snapshot and the live stream.
+    fn register_watch(&self,
+                       start_key: Key,
+                       end_key: Key,
+                       sender: Sender<WatchEvent>)
+                       -> super::Result<()> {
+        let snapshot_ts = self.current_ts();
+        let snapshot = try!(to_storage_result(self.txn()
+            .scan(&start_key, Some(&end_key), ::std::usize::MAX, snapshot_ts, false)));
+        for (key, value) in snapshot {
+            // A closed receiver just means the subscriber gave up before
+            // the snapshot finished replaying; nothing left to register.
+            if sender.send((key, Some(value), snapshot_ts)).is_err() {
+                return Ok(());
+            }
+        }
+        self.watchers.lock().unwrap().push(Watcher {
+            start_key: start_key,
+            end_key: end_key,
+            sender: sender,
+        });
+        Ok(())
+    }
+
+    fn handle_cas(&self, key: Key, expected: Option<Value>, new: Option<Value>) -> super::Result<()> {
+        let txn = self.txn();
+        // Compare against the latest committed value, not whatever was
+        // visible as of the client's `start_ts`: CAS commits its own
+        // writes at internally-allocated timestamps above the client
+        // clock (see `alloc_ts`), so an earlier CAS's result can already
+        // sit above a later CAS's `start_ts` and would otherwise look
+        // absent, letting `create_if_not_exists` fire twice.
+        let current = try!(to_storage_result(txn.get(&key, self.current_ts())));
+        if current != expected {
+            return Err(super::Error::CasFailed(key));
+        }
+        let prewrite_ts = self.alloc_ts();
+        let commit_ts = self.alloc_ts();
+        let write = match new {
+            Some(value) => Write::Put((key.clone(), value)),
+            None => Write::Delete(key.clone()),
+        };
+        try!(to_storage_result(txn.prewrite(vec![write], prewrite_ts)));
+        let changes = try!(to_storage_result(txn.commit(prewrite_ts, commit_ts)));
+        self.dispatch_watch_events(changes, commit_ts);
+        Ok(())
+    }
+
+    /// Serializes every committed record in the engine to CBOR bytes.
+    fn handle_dump(&self) -> super::Result<Vec<u8>> {
+        let records = try!(to_storage_result(self.txn().all_records()));
+        serde_cbor::to_vec(&records).map_err(super::Error::from)
+    }
+
+    /// Replaces the engine's entire contents with a CBOR snapshot
+    /// produced by `handle_dump` (see `MvccTxn::restore`), refusing to do
+    /// so while any transaction still holds a prewrite lock: restoring
+    /// underneath an in-flight commit would silently discard or
+    /// resurrect data the lock's owner is relying on.
+    fn handle_restore(&self, bytes: Vec<u8>) -> super::Result<()> {
+        if !self.locks.lock().unwrap().is_empty() {
+            return Err(super::Error::RestoreConflict);
+        }
+        let records: Vec<Record> = try!(serde_cbor::from_slice(&bytes).map_err(super::Error::from));
+        to_storage_result(self.txn().restore(records))
+    }
+}
+
+fn to_storage_err(err: super::mvcc::Error) -> super::Error {
+    super::Error::from(Error::from(err))
+}
+
+fn to_storage_result<T>(r: super::mvcc::Result<T>) -> super::Result<T> {
+    r.map_err(to_storage_err)
+}